@@ -1,9 +1,11 @@
-use clap::Parser;
+use clap::{ArgAction, Parser};
 use encoding_rs::Encoding;
-use qrcode::{render::unicode::Dense1x2, QrCode, Version};
+use qrcode::render::{svg, unicode::Dense1x2};
+use qrcode::{QrCode, Version};
 use std::fmt;
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Write};
+use std::path::PathBuf;
 use std::process::{ExitCode, Termination};
 
 #[derive(Debug)]
@@ -81,6 +83,9 @@ enum Command {
 
     /// Encodes QR Code from a string
     Encode(EncodeArgs),
+
+    /// Encodes an otpauth:// URI for provisioning an authenticator app
+    Totp(TotpArgs),
 }
 
 fn main() -> ExitCode {
@@ -88,6 +93,7 @@ fn main() -> ExitCode {
     let res = match command {
         Command::Decode(args) => decode(args),
         Command::Encode(args) => encode(args),
+        Command::Totp(args) => totp(args),
     };
 
     if let Err(e) = res {
@@ -98,38 +104,270 @@ fn main() -> ExitCode {
     }
 }
 
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum DecodeFormat {
+    Text,
+    Raw,
+    Hex,
+    Base64,
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes `data` as standard (padded) RFC 4648 base64.
+fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let b0 = chunk[0];
+        let b1 = chunk.get(1).copied().unwrap_or(0);
+        let b2 = chunk.get(2).copied().unwrap_or(0);
+        let n = ((b0 as u32) << 16) | ((b1 as u32) << 8) | b2 as u32;
+
+        out.push(BASE64_ALPHABET[((n >> 18) & 0x3f) as usize] as char);
+        out.push(BASE64_ALPHABET[((n >> 12) & 0x3f) as usize] as char);
+        out.push(if chunk.len() > 1 {
+            BASE64_ALPHABET[((n >> 6) & 0x3f) as usize] as char
+        } else {
+            '='
+        });
+        out.push(if chunk.len() > 2 {
+            BASE64_ALPHABET[(n & 0x3f) as usize] as char
+        } else {
+            '='
+        });
+    }
+    out
+}
+
+/// Encodes `data` as lowercase hex.
+fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
 #[derive(clap::Args)]
 struct DecodeArgs {
     /// Character encoding to use.
     #[clap(short, long, default_value = "UTF-8")]
     encoding: String,
 
+    /// Output format for the decoded content.
+    #[clap(long, value_enum, default_value_t = DecodeFormat::Text)]
+    format: DecodeFormat,
+
+    /// Prints the version/ECC/mask banner even for binary formats.
+    #[clap(long)]
+    verbose: bool,
+
+    /// Emits an array of objects, one per detected grid, with metadata and
+    /// corner coordinates, instead of the format chosen by --format.
+    #[clap(long, conflicts_with_all = ["format", "verbose"])]
+    json: bool,
+
+    /// If no grid is found, or any grid fails to decode, retries against
+    /// binarized, upscaled and rotated copies of the image before giving up.
+    #[clap(long)]
+    robust: bool,
+
     /// Path to the image to decode.
     image: std::path::PathBuf,
 }
 
+/// A single decoded QR Code symbol, detached from the `rqrr::Grid` it was
+/// read from so it can be produced by either the original image or one of
+/// the `--robust` fallback transforms.
+struct Symbol {
+    content: Vec<u8>,
+    meta: rqrr::MetaData,
+    corners: [(i32, i32); 4],
+}
+
+fn corners_of<G>(grid: &rqrr::Grid<G>) -> [(i32, i32); 4] {
+    grid.bounds.map(|point| (point.x, point.y))
+}
+
+/// Detects and decodes every symbol in `luma`, failing on the first grid
+/// that cannot be decoded.
+fn detect_symbols(luma: image::GrayImage) -> Result<Vec<Symbol>> {
+    let mut prepared = rqrr::PreparedImage::prepare(luma);
+    let mut symbols = Vec::new();
+
+    for grid in prepared.detect_grids() {
+        let corners = corners_of(&grid);
+        let mut content = vec![];
+        let meta = grid.decode_to(&mut content)?;
+        symbols.push(Symbol {
+            content,
+            meta,
+            corners,
+        });
+    }
+
+    Ok(symbols)
+}
+
+/// Detects and decodes the first symbol in `luma`, skipping over any grid
+/// that fails to decode instead of failing outright.
+fn detect_first_symbol(luma: image::GrayImage) -> Option<Symbol> {
+    let mut prepared = rqrr::PreparedImage::prepare(luma);
+
+    prepared.detect_grids().into_iter().find_map(|grid| {
+        let corners = corners_of(&grid);
+        let mut content = vec![];
+        grid.decode_to(&mut content).ok().map(|meta| Symbol {
+            content,
+            meta,
+            corners,
+        })
+    })
+}
+
+fn binarize(img: &image::GrayImage, threshold: u8) -> image::GrayImage {
+    image::GrayImage::from_fn(img.width(), img.height(), |x, y| {
+        image::Luma(if img.get_pixel(x, y).0[0] < threshold {
+            [0]
+        } else {
+            [255]
+        })
+    })
+}
+
+fn upscale_2x(img: &image::GrayImage) -> image::GrayImage {
+    image::imageops::resize(
+        img,
+        img.width() * 2,
+        img.height() * 2,
+        image::imageops::FilterType::Nearest,
+    )
+}
+
+/// Re-runs detection over binarized, upscaled and rotated copies of `luma`,
+/// returning the first symbol that decodes cleanly along with a label
+/// describing the transform that succeeded.
+fn detect_symbols_robust(luma: &image::GrayImage) -> Option<(Symbol, String)> {
+    let mut attempts: Vec<(String, image::GrayImage)> = vec![
+        ("threshold=64".into(), binarize(luma, 64)),
+        ("threshold=128".into(), binarize(luma, 128)),
+        ("threshold=192".into(), binarize(luma, 192)),
+        ("upscale=2x".into(), upscale_2x(luma)),
+        ("rotate=90".into(), image::imageops::rotate90(luma)),
+        ("rotate=180".into(), image::imageops::rotate180(luma)),
+        ("rotate=270".into(), image::imageops::rotate270(luma)),
+    ];
+
+    for (label, candidate) in attempts.drain(..) {
+        if let Some(symbol) = detect_first_symbol(candidate) {
+            return Some((symbol, label));
+        }
+    }
+
+    None
+}
+
+/// Escapes `s` for embedding in a JSON string literal.
+fn json_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn decode_json(symbols: &[Symbol], encoding: &'static Encoding) -> Result<()> {
+    let mut items = Vec::new();
+
+    for symbol in symbols {
+        let (text, _, has_error) = encoding.decode(&symbol.content);
+        if has_error {
+            eprintln!("warning: failed to decode content");
+        }
+
+        let corners: Vec<String> = symbol
+            .corners
+            .iter()
+            .map(|(x, y)| format!("{{\"x\":{x},\"y\":{y}}}"))
+            .collect();
+
+        items.push(format!(
+            "{{\"version\":{},\"ecc_level\":{},\"mask\":{},\"content\":\"{}\",\"bytes\":\"{}\",\"corners\":[{}]}}",
+            symbol.meta.version.to_size(),
+            symbol.meta.ecc_level,
+            symbol.meta.mask,
+            json_escape(&text),
+            base64_encode(&symbol.content),
+            corners.join(","),
+        ));
+    }
+
+    println!("[{}]", items.join(","));
+    Ok(())
+}
+
 fn decode(args: DecodeArgs) -> Result<()> {
     let encoding = match Encoding::for_label(args.encoding.as_bytes()) {
         Some(encoding) => encoding,
         None => return Err(format!("Unsupported encoding: {}", args.encoding).into()),
     };
 
-    let img = image::open(&args.image)?.to_luma8();
-    let mut img = rqrr::PreparedImage::prepare(img);
+    let luma = image::open(&args.image)?.to_luma8();
+    let mut symbols = match detect_symbols(luma.clone()) {
+        Ok(symbols) => symbols,
+        Err(e) if args.robust => {
+            eprintln!("warning: {e}");
+            Vec::new()
+        }
+        Err(e) => return Err(e),
+    };
 
-    for grid in img.detect_grids() {
-        let mut content = vec![];
-        let meta = grid.decode_to(&mut content)?;
+    if symbols.is_empty() && args.robust {
+        match detect_symbols_robust(&luma) {
+            Some((symbol, label)) => {
+                eprintln!("robust: recovered using {label}");
+                symbols.push(symbol);
+            }
+            None => eprintln!("robust: no transform succeeded"),
+        }
+    }
 
-        let (content, _, has_error) = encoding.decode(content.as_slice());
-        if has_error {
-            eprintln!("warning: failed to decode content");
+    if args.json {
+        return decode_json(&symbols, encoding);
+    }
+
+    for symbol in &symbols {
+        let content = &symbol.content;
+
+        if matches!(args.format, DecodeFormat::Text) || args.verbose {
+            println!("# Version: {}", symbol.meta.version.to_size());
+            println!("# ECC Level: {}", symbol.meta.ecc_level);
+            println!("# Mask: {}", symbol.meta.mask);
         }
 
-        println!("# Version: {}", meta.version.to_size());
-        println!("# ECC Level: {}", meta.ecc_level);
-        println!("# Mask: {}", meta.mask);
-        println!("{content}");
+        match args.format {
+            DecodeFormat::Text => {
+                let (content, _, has_error) = encoding.decode(content.as_slice());
+                if has_error {
+                    eprintln!("warning: failed to decode content");
+                }
+                println!("{content}");
+            }
+            DecodeFormat::Raw => {
+                std::io::stdout().write_all(content)?;
+            }
+            DecodeFormat::Hex => {
+                println!("{}", hex_encode(content));
+            }
+            DecodeFormat::Base64 => {
+                println!("{}", base64_encode(content));
+            }
+        }
     }
 
     Ok(())
@@ -151,6 +389,119 @@ impl std::str::FromStr for EcLevel {
     }
 }
 
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum Format {
+    Png,
+    Svg,
+    Unicode,
+}
+
+/// Options controlling how a rendered QR Code is written out.
+#[derive(clap::Args)]
+struct OutputArgs {
+    /// Output format. Inferred from --output's extension if omitted, or `unicode` if
+    /// --output is not given either.
+    #[clap(long, value_enum)]
+    format: Option<Format>,
+
+    /// Path to write the rendered QR Code to. When omitted, the code is printed to
+    /// stdout (as Unicode blocks unless --format says otherwise; png needs a path).
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+
+    /// Size of each module, in pixels.
+    #[clap(long, default_value_t = 8)]
+    scale: u32,
+
+    /// Draws the standard quiet zone border around the code.
+    #[clap(long, action = ArgAction::Set, default_value_t = true)]
+    quiet_zone: bool,
+
+    /// Foreground (dark module) color, as a hex RGB value.
+    #[clap(long, default_value = "000000")]
+    dark: String,
+
+    /// Background (light module) color, as a hex RGB value.
+    #[clap(long, default_value = "ffffff")]
+    light: String,
+}
+
+fn parse_hex_color(s: &str) -> Result<(u8, u8, u8)> {
+    let s = s.strip_prefix('#').unwrap_or(s);
+    if s.len() != 6 {
+        return Err(format!("Illegal color: {s}").into());
+    }
+
+    let channel = |i: usize| {
+        u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| Error::from(format!("Illegal color: {s}")))
+    };
+    Ok((channel(0)?, channel(2)?, channel(4)?))
+}
+
+fn render(code: &QrCode, args: &OutputArgs) -> Result<()> {
+    let format = match args.format {
+        Some(format) => format,
+        None => match &args.output {
+            Some(path) => match path.extension().and_then(|ext| ext.to_str()) {
+                Some("png") => Format::Png,
+                Some("svg") => Format::Svg,
+                _ => return Err(format!("Cannot infer format from {}", path.display()).into()),
+            },
+            None => Format::Unicode,
+        },
+    };
+
+    let (dr, dg, db) = parse_hex_color(&args.dark)?;
+    let (lr, lg, lb) = parse_hex_color(&args.light)?;
+
+    match format {
+        Format::Png => {
+            let path = args
+                .output
+                .as_ref()
+                .ok_or_else(|| Error::from("--output is required for png format".to_string()))?;
+
+            let image = code
+                .render::<image::Rgb<u8>>()
+                .module_dimensions(args.scale, args.scale)
+                .quiet_zone(args.quiet_zone)
+                .dark_color(image::Rgb([dr, dg, db]))
+                .light_color(image::Rgb([lr, lg, lb]))
+                .build();
+            image.save(path)?;
+        }
+        Format::Svg => {
+            let dark = format!("#{dr:02x}{dg:02x}{db:02x}");
+            let light = format!("#{lr:02x}{lg:02x}{lb:02x}");
+            let image = code
+                .render()
+                .module_dimensions(args.scale, args.scale)
+                .quiet_zone(args.quiet_zone)
+                .dark_color(svg::Color(&dark))
+                .light_color(svg::Color(&light))
+                .build();
+            match &args.output {
+                Some(path) => std::fs::write(path, image)?,
+                None => println!("{image}"),
+            }
+        }
+        Format::Unicode => {
+            let image = code
+                .render::<Dense1x2>()
+                .quiet_zone(args.quiet_zone)
+                .dark_color(Dense1x2::Light)
+                .light_color(Dense1x2::Dark)
+                .build();
+            match &args.output {
+                Some(path) => std::fs::write(path, &image)?,
+                None => println!("{image}"),
+            }
+        }
+    }
+
+    Ok(())
+}
+
 #[derive(clap::Args)]
 struct EncodeArgs {
     /// Generates Micro QR Code. (requires --version)
@@ -172,6 +523,14 @@ struct EncodeArgs {
     /// Data to be encoded.
     #[clap(required_unless_present = "file")]
     data: Option<String>,
+
+    /// Character encoding to transcode `data` to before encoding. Ignored when
+    /// reading binary data via --file.
+    #[clap(short, long, default_value = "UTF-8")]
+    encoding: String,
+
+    #[clap(flatten)]
+    output: OutputArgs,
 }
 
 fn encode(args: EncodeArgs) -> Result<()> {
@@ -193,7 +552,16 @@ fn encode(args: EncodeArgs) -> Result<()> {
         src.read_to_end(&mut data)?;
         data
     } else if let Some(data) = &args.data {
-        data.as_bytes().to_vec()
+        let encoding = match Encoding::for_label(args.encoding.as_bytes()) {
+            Some(encoding) => encoding,
+            None => return Err(format!("Unsupported encoding: {}", args.encoding).into()),
+        };
+
+        let (data, _, has_error) = encoding.encode(data);
+        if has_error {
+            eprintln!("warning: failed to encode content");
+        }
+        data.into_owned()
     } else {
         unreachable!()
     };
@@ -203,12 +571,138 @@ fn encode(args: EncodeArgs) -> Result<()> {
         None => QrCode::with_error_correction_level(&data, args.level.0)?,
     };
 
-    let image = code
-        .render::<Dense1x2>()
-        .dark_color(Dense1x2::Light)
-        .light_color(Dense1x2::Dark)
-        .build();
-    println!("{image}");
+    render(&code, &args.output)
+}
 
-    Ok(())
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum Algorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    fn as_str(self) -> &'static str {
+        match self {
+            Algorithm::Sha1 => "SHA1",
+            Algorithm::Sha256 => "SHA256",
+            Algorithm::Sha512 => "SHA512",
+        }
+    }
+}
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Encodes `data` as unpadded RFC 4648 base32.
+fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity((data.len() * 8 + 4) / 5);
+    let mut buf = 0u32;
+    let mut bits = 0u32;
+
+    for &byte in data {
+        buf = (buf << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buf >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buf << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    out
+}
+
+/// Validates that `input` is RFC 4648 base32 and strips any padding,
+/// returning the unpadded uppercase form.
+fn normalize_base32(input: &str) -> Result<String> {
+    let unpadded = input.trim_end_matches('=').to_uppercase();
+    if unpadded.is_empty() || !unpadded.bytes().all(|b| BASE32_ALPHABET.contains(&b)) {
+        return Err(format!("Illegal base32 secret: {input}").into());
+    }
+    Ok(unpadded)
+}
+
+/// Percent-encodes everything but RFC 3986 unreserved characters.
+fn percent_encode(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(byte as char);
+            }
+            _ => out.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    out
+}
+
+#[derive(clap::Args)]
+struct TotpArgs {
+    /// Issuer displayed in the authenticator app.
+    #[clap(long)]
+    issuer: String,
+
+    /// Account name displayed in the authenticator app.
+    #[clap(long)]
+    account: String,
+
+    /// Shared secret, as raw bytes. Mutually exclusive with --base32.
+    #[clap(long, conflicts_with = "base32", required_unless_present = "base32")]
+    secret: Option<String>,
+
+    /// Shared secret, already encoded as base32.
+    #[clap(long)]
+    base32: Option<String>,
+
+    /// HMAC algorithm used to generate codes.
+    #[clap(long, value_enum, default_value = "sha1")]
+    algorithm: Algorithm,
+
+    /// Number of digits in the generated code.
+    #[clap(long, default_value_t = 6)]
+    digits: u32,
+
+    /// Validity period of each code, in seconds.
+    #[clap(long, default_value_t = 30)]
+    period: u64,
+
+    /// The error correction level. (L/M/Q/H)
+    #[clap(short, long, default_value = "L")]
+    level: EcLevel,
+
+    #[clap(flatten)]
+    output: OutputArgs,
+}
+
+fn totp(args: TotpArgs) -> Result<()> {
+    if !matches!(args.digits, 6 | 8) {
+        return Err(format!("Unsupported digits: {}", args.digits).into());
+    }
+    if args.period == 0 {
+        return Err("--period must be greater than zero".to_string().into());
+    }
+
+    let secret = match &args.base32 {
+        Some(base32) => normalize_base32(base32)?,
+        None => base32_encode(args.secret.as_ref().unwrap().as_bytes()),
+    };
+
+    let label = format!(
+        "{}:{}",
+        percent_encode(&args.issuer),
+        percent_encode(&args.account)
+    );
+    let uri = format!(
+        "otpauth://totp/{label}?secret={secret}&issuer={issuer}&algorithm={algorithm}&digits={digits}&period={period}",
+        issuer = percent_encode(&args.issuer),
+        algorithm = args.algorithm.as_str(),
+        digits = args.digits,
+        period = args.period,
+    );
+
+    let code = QrCode::with_error_correction_level(&uri, args.level.0)?;
+
+    render(&code, &args.output)
 }